@@ -1,13 +1,21 @@
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 use dittolive_ditto::{identity::*, prelude::*, store::dql::QueryResultItem};
+use identity_mode::IdentityArgs;
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType};
+use object_store::{aws::AmazonS3Builder, local::LocalFileSystem, path::Path as ObjectPath, ObjectStore};
 use std::{
     self,
     collections::HashMap,
+    fs::{self, File},
     path::{Path, PathBuf},
     str::FromStr,
     sync::Arc,
 };
+use url::Url;
+
+#[path = "../identity_mode.rs"]
+mod identity_mode;
 
 /// A sample app to demo Ditto's Rust SDK, see long '--help' for examples
 ///
@@ -41,9 +49,8 @@ struct Args {
     #[clap(long, env = "APP_ID")]
     app_id: String,
 
-    /// The Playground token used to authenticate (found at portal.ditto.live)
-    #[clap(long, env = "PLAYGROUND_TOKEN")]
-    playground_token: String,
+    #[clap(flatten)]
+    identity: IdentityArgs,
 }
 
 #[derive(Debug, Subcommand)]
@@ -52,11 +59,21 @@ enum Cmd {
         /// Path to a file to upload as an attachment
         #[clap(long)]
         path: PathBuf,
+
+        /// Also generate and store a downscaled JPEG thumbnail as a second
+        /// attachment, if the file decodes as an image
+        #[clap(long)]
+        thumbnail: bool,
     },
     DownloadPhoto {
         /// Name of the attachment file to download
         #[clap(long)]
         name: String,
+
+        /// Mirror the downloaded attachment to an external object store,
+        /// e.g. `file:///tmp/mirror` or `s3://my-bucket/photos`
+        #[clap(long)]
+        mirror_to: Option<Url>,
     },
 }
 
@@ -68,20 +85,12 @@ async fn main() -> Result<()> {
     // Initialize Ditto SDK client
     let args = &cli.args;
     let app_id = AppId::from_str(&args.app_id)?;
+    let resolved_identity = args.identity.resolve()?;
     let ditto = Ditto::builder()
         .with_root(Arc::new(PersistentRoot::from_current_exe()?))
         .with_minimum_log_level(LogLevel::Debug)
         .with_identity(move |ditto_root| {
-            let shared_token = args.playground_token.clone();
-            let enable_cloud_sync = true;
-            let custom_auth_url = None;
-            OnlinePlayground::new(
-                ditto_root,
-                app_id,
-                shared_token,
-                enable_cloud_sync,
-                custom_auth_url,
-            )
+            identity_mode::build_identity(resolved_identity, ditto_root, app_id)
         })?
         .build()?;
 
@@ -90,43 +99,137 @@ async fn main() -> Result<()> {
     let store = ditto.store();
 
     match cli.cmd {
-        Cmd::UploadPhoto { path } => {
-            upload_photo(store, &path).await?;
+        Cmd::UploadPhoto { path, thumbnail } => {
+            upload_photo(store, &path, thumbnail).await?;
         }
-        Cmd::DownloadPhoto { name } => {
-            download_photo(store, &name).await?;
+        Cmd::DownloadPhoto { name, mirror_to } => {
+            download_photo(store, &name, mirror_to).await?;
         }
     }
 
     Ok(())
 }
 
-/// Upload a photo (or arbitrary file) to the Ditto Store from a Path
-async fn upload_photo(store: &Store, path: &Path) -> Result<()> {
+/// Upload a photo (or arbitrary file) to the Ditto Store from a Path,
+/// optionally generating a downscaled JPEG thumbnail as a second attachment
+async fn upload_photo(store: &Store, path: &Path, thumbnail: bool) -> Result<()> {
     let photo_name = path.file_name().and_then(|s| s.to_str()).unwrap_or("photo");
 
     let photo_attachment = store.new_attachment(path, HashMap::default()).await?;
-    let _result = store
-        .execute(
-            "INSERT INTO COLLECTION photos (photo_attachment ATTACHMENT) DOCUMENTS (:photo_doc)",
-            Some(
-                serde_json::json!({
-                    "photo_doc": {
-                        "photo_name": photo_name,
-                        "photo_attachment": photo_attachment
-                    }
-                })
-                .into(),
-            ),
-        )
-        .await?;
-
-    println!("Uploaded photo with name '{photo_name}'");
+
+    let thumbnail_attachment = if thumbnail {
+        match make_thumbnail(path)? {
+            Some((thumbnail_path, metadata)) => {
+                Some(store.new_attachment(&thumbnail_path, metadata).await?)
+            }
+            None => {
+                println!("'{photo_name}' doesn't look like an image, skipping thumbnail");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    match &thumbnail_attachment {
+        Some(thumbnail_attachment) => {
+            store
+                .execute(
+                    "INSERT INTO COLLECTION photos \
+                     (photo_attachment ATTACHMENT, thumbnail_attachment ATTACHMENT) \
+                     DOCUMENTS (:photo_doc)",
+                    Some(
+                        serde_json::json!({
+                            "photo_doc": {
+                                "photo_name": photo_name,
+                                "photo_attachment": photo_attachment,
+                                "thumbnail_attachment": thumbnail_attachment
+                            }
+                        })
+                        .into(),
+                    ),
+                )
+                .await?;
+        }
+        None => {
+            store
+                .execute(
+                    "INSERT INTO COLLECTION photos (photo_attachment ATTACHMENT) DOCUMENTS (:photo_doc)",
+                    Some(
+                        serde_json::json!({
+                            "photo_doc": {
+                                "photo_name": photo_name,
+                                "photo_attachment": photo_attachment
+                            }
+                        })
+                        .into(),
+                    ),
+                )
+                .await?;
+        }
+    }
+
+    let suffix = if thumbnail_attachment.is_some() {
+        " with thumbnail"
+    } else {
+        ""
+    };
+    println!("Uploaded photo with name '{photo_name}'{suffix}");
     Ok(())
 }
 
-/// Download a photo (or arbitrary file) from the Ditto Store by the file's name
-async fn download_photo(store: &Store, name: &str) -> Result<()> {
+/// Decode `path` as an image and produce a downscaled JPEG preview that fits
+/// within a 256x256 box (preserving aspect ratio), returning the temp file
+/// path along with width/height/content-type metadata for the thumbnail
+/// attachment. Returns `Ok(None)` if `path` doesn't decode as an image.
+fn make_thumbnail(path: &Path) -> Result<Option<(PathBuf, HashMap<String, String>)>> {
+    let image = match image::open(path) {
+        Ok(image) => image,
+        Err(_) => return Ok(None),
+    };
+
+    let (width, height) = (image.width(), image.height());
+    let (new_width, new_height) = if width >= height {
+        let new_width = width.min(256);
+        let new_height = (height as f64 * new_width as f64 / width as f64).round() as u32;
+        (new_width, new_height)
+    } else {
+        let new_height = height.min(256);
+        let new_width = (width as f64 * new_height as f64 / height as f64).round() as u32;
+        (new_width, new_height)
+    };
+
+    let thumbnail = image.resize(new_width, new_height, FilterType::Lanczos3);
+    // JPEG has no alpha channel, so flatten to RGB8 first: the raw JpegEncoder
+    // (unlike `DynamicImage::save`/`write_to`) doesn't do this conversion itself,
+    // and will error on any other pixel format (e.g. ImageRgba8 from a PNG with
+    // transparency), which previously took the whole upload down with it.
+    let thumbnail = thumbnail.to_rgb8();
+
+    let thumbnail_path = std::env::temp_dir().join(format!(
+        "{}-thumbnail.jpg",
+        path.file_stem().and_then(|s| s.to_str()).unwrap_or("photo")
+    ));
+    let file = File::create(&thumbnail_path).context("failed to create thumbnail temp file")?;
+    JpegEncoder::new_with_quality(file, 80)
+        .encode_image(&thumbnail)
+        .context("failed to encode thumbnail as JPEG")?;
+
+    // `resize` picks its own aspect-preserving size within the requested box,
+    // which can differ from (new_width, new_height) after rounding
+    let metadata = HashMap::from([
+        ("width".to_string(), thumbnail.width().to_string()),
+        ("height".to_string(), thumbnail.height().to_string()),
+        ("content_type".to_string(), "image/jpeg".to_string()),
+    ]);
+
+    Ok(Some((thumbnail_path, metadata)))
+}
+
+/// Download a photo (or arbitrary file) from the Ditto Store by the file's
+/// name, optionally mirroring the finished download to an external object
+/// store keyed by that name
+async fn download_photo(store: &Store, name: &str, mirror_to: Option<Url>) -> Result<()> {
     // Query and wait for the attachment to download
     let result_item = receive_photo_document(store, name).await?;
 
@@ -143,6 +246,13 @@ async fn download_photo(store: &Store, name: &str) -> Result<()> {
         .get("id")
         .context("failed to get ID of attachment")?
         .clone(); // Cloned to move into closure below
+    let photo_name = name.to_string();
+
+    // Signals once the fetch is Completed, carrying the mirror task (if any)
+    // so this function doesn't return - and tear the fetcher down - before
+    // the attachment is actually on disk, or before it's finished mirroring
+    let (completed_tx, completed_rx) = tokio::sync::oneshot::channel();
+    let mut completed_tx = Some(completed_tx);
 
     let _fetcher = store.fetch_attachment(photo_attachment_token, move |event| {
         use DittoAttachmentFetchEvent::*;
@@ -155,11 +265,74 @@ async fn download_photo(store: &Store, name: &str) -> Result<()> {
             }
             Completed { attachment } => {
                 println!("Successfully downloaded attachment {photo_id:?} to path {}", attachment.path().display());
+
+                // Mirroring shouldn't hold up this callback, so hand it off to its own task
+                let mirror_task = mirror_to.clone().map(|mirror_to| {
+                    let photo_name = photo_name.clone();
+                    let path = attachment.path().to_path_buf();
+                    tokio::spawn(async move {
+                        if let Err(err) = mirror_attachment(&mirror_to, &photo_name, &path).await {
+                            eprintln!("Failed to mirror attachment {photo_name:?}: {err}");
+                        }
+                    })
+                });
+
+                _ = completed_tx.take().map(|tx| tx.send(mirror_task));
             }
             Deleted => panic!("attachment should not get deleted while fetching"),
         }
     })?;
 
+    if let Some(mirror_task) = completed_rx.await? {
+        mirror_task.await?;
+    }
+
+    Ok(())
+}
+
+/// Upload a downloaded attachment to an external object store, resolving the
+/// backend from the URL scheme (`file://` for a local directory, `s3://` for
+/// an S3-compatible endpoint), keyed by the document's `photo_name`
+async fn mirror_attachment(mirror_to: &Url, photo_name: &str, path: &Path) -> Result<()> {
+    let (object_store, object_path, location): (Box<dyn ObjectStore>, ObjectPath, String) =
+        match mirror_to.scheme() {
+            "file" => {
+                let dir = mirror_to
+                    .to_file_path()
+                    .map_err(|_| anyhow::anyhow!("invalid file:// URL: {mirror_to}"))?;
+                let object_path = ObjectPath::from(photo_name);
+                let location = format!("{}/{object_path}", mirror_to.as_str().trim_end_matches('/'));
+                (
+                    Box::new(LocalFileSystem::new_with_prefix(dir)?),
+                    object_path,
+                    location,
+                )
+            }
+            "s3" => {
+                let bucket = mirror_to
+                    .host_str()
+                    .context("s3:// URL is missing a bucket name")?;
+                let prefix = mirror_to.path().trim_matches('/');
+                let key = if prefix.is_empty() {
+                    photo_name.to_string()
+                } else {
+                    format!("{prefix}/{photo_name}")
+                };
+                let object_path = ObjectPath::from(key);
+                let location = format!("s3://{bucket}/{object_path}");
+                (
+                    Box::new(AmazonS3Builder::from_env().with_bucket_name(bucket).build()?),
+                    object_path,
+                    location,
+                )
+            }
+            scheme => anyhow::bail!("unsupported mirror backend scheme: {scheme}://"),
+        };
+
+    let bytes = fs::read(path).context("failed to read downloaded attachment")?;
+    object_store.put(&object_path, bytes.into()).await?;
+
+    println!("Mirrored attachment '{photo_name}' to {location}");
     Ok(())
 }
 
@@ -1,8 +1,19 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use dittolive_ditto::{identity::*, prelude::*, store::dql::QueryResult};
+use identity_mode::IdentityArgs;
 use serde::{Deserialize, Serialize};
-use std::{self, str::FromStr, sync::Arc};
+use std::{
+    self,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+#[path = "../identity_mode.rs"]
+mod identity_mode;
 
 /// A sample app to demo Ditto's Rust SDK, see long '--help' for examples
 ///
@@ -41,9 +52,8 @@ struct Args {
     #[clap(long, env = "APP_ID")]
     app_id: String,
 
-    /// The Playground token used to authenticate (found at <https://portal.ditto.live>)
-    #[clap(long, env = "PLAYGROUND_TOKEN")]
-    playground_token: String,
+    #[clap(flatten)]
+    identity: IdentityArgs,
 }
 
 #[derive(Debug, Subcommand)]
@@ -64,6 +74,31 @@ enum Cmd {
         #[clap(long)]
         color: String,
     },
+    /// Register a live query on "car" documents and stream added/updated/removed
+    /// documents to stdout as the result set changes, until Ctrl-C
+    WatchCars {
+        /// Only watch cars with this color
+        #[clap(long)]
+        color: Option<String>,
+    },
+    /// Increment the view_count counter for every car with a given make
+    IncrementViews {
+        /// Increment the view count for cars with this make
+        #[clap(long)]
+        make: String,
+    },
+    /// Bulk-import "car" documents from a JSON or CSV file
+    ImportCars {
+        /// Path to a .json or .csv file containing car records
+        #[clap(long)]
+        file: PathBuf,
+    },
+    /// Bulk-export all "car" documents to a JSON or CSV file
+    ExportCars {
+        /// Path to write a .json or .csv file of car records
+        #[clap(long)]
+        file: PathBuf,
+    },
 }
 
 #[tokio::main]
@@ -74,20 +109,12 @@ async fn main() -> Result<()> {
     // Initialize Ditto SDK client
     let args = &cli.args;
     let app_id = AppId::from_str(&args.app_id)?;
+    let resolved_identity = args.identity.resolve()?;
     let ditto = Ditto::builder()
         .with_root(Arc::new(PersistentRoot::from_current_exe()?))
         .with_minimum_log_level(LogLevel::Debug)
         .with_identity(move |ditto_root| {
-            let shared_token = args.playground_token.clone();
-            let enable_cloud_sync = true;
-            let custom_auth_url = None;
-            OnlinePlayground::new(
-                ditto_root,
-                app_id,
-                shared_token,
-                enable_cloud_sync,
-                custom_auth_url,
-            )
+            identity_mode::build_identity(resolved_identity, ditto_root, app_id)
         })?
         .build()?;
 
@@ -96,7 +123,7 @@ async fn main() -> Result<()> {
 
     match cli.cmd {
         Cmd::InsertCar { make, color } => {
-            let car = Car { color, make };
+            let car = Car::new(color, make);
             let result_set = dql_insert_car(&ditto, &car).await?;
             let mutations = result_set.mutated_document_ids();
             let s = if mutations.len() == 1 { "" } else { "s" };
@@ -111,25 +138,89 @@ async fn main() -> Result<()> {
                 println!("Car with color={color}: {car:?}");
             }
         }
+        Cmd::WatchCars { color } => {
+            watch_cars(&ditto, color).await?;
+        }
+        Cmd::IncrementViews { make } => {
+            let result_set = dql_increment_views(&ditto, &make).await?;
+            let mutations = result_set.mutated_document_ids();
+            let s = if mutations.len() == 1 { "" } else { "s" };
+            println!("Incremented view_count for {} car{s} with make={make}", mutations.len());
+        }
+        Cmd::ImportCars { file } => {
+            let cars = read_cars_file(&file)?;
+            let result_set = dql_import_cars(&ditto, &cars).await?;
+            let mutations = result_set.mutated_document_ids();
+            let s = if mutations.len() == 1 { "" } else { "s" };
+            println!("Imported {} car{s} from {}", mutations.len(), file.display());
+        }
+        Cmd::ExportCars { file } => {
+            let cars = dql_select_all_cars(&ditto).await?;
+            write_cars_file(&file, &cars)?;
+            println!("Exported {} car(s) to {}", cars.len(), file.display());
+        }
     }
 
     Ok(())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct Car {
     pub color: String,
     pub make: String,
+    /// Number of times this car has been viewed. Backed by a CRDT counter
+    /// (see `dittolive_ditto::types::DittoCounter`) declared on the `cars`
+    /// collection, so increments from offline peers merge as a sum instead
+    /// of one peer's write clobbering another's. Counters can't be set to an
+    /// arbitrary value via a plain INSERT, so `dql_insert_car`/`dql_import_cars`
+    /// serialize a car's color/make by hand rather than serializing `Car`
+    /// directly. After insertion the count changes only through PN_INCREMENT
+    /// updates (`increment-views`, and `import-cars` restoring imported counts).
+    #[serde(default)]
+    pub view_count: i64,
+}
+
+impl Car {
+    fn new(color: String, make: String) -> Self {
+        Self {
+            color,
+            make,
+            view_count: 0,
+        }
+    }
 }
 
 async fn dql_insert_car(ditto: &Ditto, car: &Car) -> Result<QueryResult> {
     let store = ditto.store();
     let query_result = store
         .execute(
-            "INSERT INTO cars DOCUMENTS (:newCar)",
+            "INSERT INTO COLLECTION cars (view_count COUNTER) DOCUMENTS (:newCar)",
             Some(
                 serde_json::json!({
-                    "newCar": car
+                    "newCar": {
+                        "color": car.color,
+                        "make": car.make
+                    }
+                })
+                .into(),
+            ),
+        )
+        .await?;
+
+    Ok(query_result)
+}
+
+/// Apply a PN-counter increment to view_count for every car with the given
+/// make. Two offline peers that each increment the same car's view_count
+/// converge to the sum of their increments rather than clobbering one another.
+async fn dql_increment_views(ditto: &Ditto, make: &str) -> Result<QueryResult> {
+    let store = ditto.store();
+    let query_result = store
+        .execute(
+            "UPDATE cars APPLY view_count PN_INCREMENT BY 1 WHERE make = :make",
+            Some(
+                serde_json::json!({
+                    "make": make
                 })
                 .into(),
             ),
@@ -161,3 +252,231 @@ async fn dql_select_cars(ditto: &Ditto, color: &str) -> Result<Vec<Car>> {
 
     Ok(cars)
 }
+
+/// Insert every car in `cars` in a single batched DQL mutation, then restore
+/// each car's recorded view_count with a follow-up PN_INCREMENT (counters
+/// can't be set to an arbitrary value directly, only incremented), so a
+/// round-tripped export/import doesn't reset every car's view count to 0
+async fn dql_import_cars(ditto: &Ditto, cars: &[Car]) -> Result<QueryResult> {
+    let store = ditto.store();
+    let docs: Vec<_> = cars
+        .iter()
+        .map(|car| serde_json::json!({ "color": car.color, "make": car.make }))
+        .collect();
+    let query_result = store
+        .execute(
+            "INSERT INTO COLLECTION cars (view_count COUNTER) DOCUMENTS (:newCars)",
+            Some(
+                serde_json::json!({
+                    "newCars": docs
+                })
+                .into(),
+            ),
+        )
+        .await?;
+
+    for car in cars {
+        if car.view_count != 0 {
+            store
+                .execute(
+                    "UPDATE cars APPLY view_count PN_INCREMENT BY :amount \
+                     WHERE color = :color AND make = :make",
+                    Some(
+                        serde_json::json!({
+                            "amount": car.view_count,
+                            "color": car.color,
+                            "make": car.make
+                        })
+                        .into(),
+                    ),
+                )
+                .await?;
+        }
+    }
+
+    Ok(query_result)
+}
+
+/// Select every car in the "cars" collection
+async fn dql_select_all_cars(ditto: &Ditto) -> Result<Vec<Car>> {
+    let store = ditto.store();
+    let query_result = store.execute("SELECT * FROM cars", None).await?;
+
+    let cars = query_result
+        .iter()
+        .map(|query_item| query_item.deserialize_value::<Car>())
+        .collect::<Result<Vec<Car>, _>>()?;
+
+    Ok(cars)
+}
+
+/// Read a list of cars from a `.json` or `.csv` file, dispatching on extension
+fn read_cars_file(path: &Path) -> Result<Vec<Car>> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => {
+            let bytes = fs::read(path)?;
+            Ok(serde_json::from_slice(&bytes)?)
+        }
+        Some("csv") => {
+            let mut reader = csv::Reader::from_path(path)?;
+            reader
+                .deserialize::<Car>()
+                .collect::<Result<Vec<Car>, _>>()
+                .map_err(Into::into)
+        }
+        other => anyhow::bail!("unsupported file extension {other:?}, expected .json or .csv"),
+    }
+}
+
+/// Write a list of cars to a `.json` or `.csv` file, dispatching on extension
+fn write_cars_file(path: &Path, cars: &[Car]) -> Result<()> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => {
+            let file = fs::File::create(path)?;
+            serde_json::to_writer_pretty(file, cars)?;
+        }
+        Some("csv") => {
+            let mut writer = csv::Writer::from_path(path)?;
+            for car in cars {
+                writer.serialize(car)?;
+            }
+            writer.flush()?;
+        }
+        other => anyhow::bail!("unsupported file extension {other:?}, expected .json or .csv"),
+    }
+
+    Ok(())
+}
+
+/// Register a sync subscription and a live observer on "car" documents,
+/// printing added/updated/removed documents as the result set changes,
+/// until the process receives Ctrl-C
+async fn watch_cars(ditto: &Ditto, color: Option<String>) -> Result<()> {
+    let store = ditto.store();
+    let query = match &color {
+        Some(_) => "SELECT * FROM cars WHERE color = :myColor",
+        None => "SELECT * FROM cars",
+    };
+    let query_args = |color: &Option<String>| {
+        color
+            .as_ref()
+            .map(|color| serde_json::json!({ "myColor": color }).into())
+    };
+
+    // Keep a sync subscription alive so remote peers' changes reach our local store
+    let _subscription = store.register_subscription(query, query_args(&color))?;
+
+    let seen: Arc<Mutex<HashMap<String, Car>>> = Arc::new(Mutex::new(HashMap::new()));
+    let observer_seen = Arc::clone(&seen);
+    let observer = store.register_observer(query, query_args(&color), move |query_result| {
+        let mut seen = observer_seen.lock().unwrap();
+        let mut current = HashMap::new();
+        for query_item in query_result.iter() {
+            let id_value = &query_item.value()["_id"];
+            let id = id_value
+                .as_str()
+                .map(str::to_string)
+                .unwrap_or_else(|| id_value.to_string());
+            match query_item.deserialize_value::<Car>() {
+                Ok(car) => {
+                    current.insert(id, car);
+                }
+                Err(err) => eprintln!("Failed to deserialize car: {err}"),
+            }
+        }
+
+        for (id, car) in &current {
+            match seen.get(id) {
+                None => println!("+ added car {id}: {car:?}"),
+                Some(previous) if previous != car => println!("~ updated car {id}: {car:?}"),
+                Some(_) => {}
+            }
+        }
+        for (id, car) in seen.iter() {
+            if !current.contains_key(id) {
+                println!("- removed car {id}: {car:?}");
+            }
+        }
+
+        *seen = current;
+    })?;
+
+    let suffix = color
+        .map(|color| format!(" with color={color}"))
+        .unwrap_or_default();
+    println!("Watching cars{suffix}, press Ctrl-C to stop...");
+    tokio::signal::ctrl_c().await?;
+    observer.cancel();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a fully offline, peer-to-peer Ditto instance rooted at its own
+    /// temp directory, so two "peers" in the same test don't share storage
+    fn offline_ditto(app_id: AppId, peer: &str) -> Result<Ditto> {
+        let root = std::env::temp_dir().join(format!(
+            "simple_dql-test-{peer}-{}-{}",
+            std::process::id(),
+            peer
+        ));
+        fs::create_dir_all(&root)?;
+        let ditto = Ditto::builder()
+            .with_root(Arc::new(PersistentRoot::new(root)?))
+            .with_minimum_log_level(LogLevel::Error)
+            .with_identity(move |ditto_root| OfflinePlayground::new(ditto_root, app_id))?
+            .build()?;
+        ditto.start_sync()?;
+        Ok(ditto)
+    }
+
+    /// Poll `dql_select_cars` for up to 10 seconds until `predicate` is
+    /// satisfied by the car with the given make, instead of guessing a fixed
+    /// sleep duration for peer discovery/sync to finish
+    async fn wait_for_car(
+        ditto: &Ditto,
+        color: &str,
+        make: &str,
+        predicate: impl Fn(&Car) -> bool,
+    ) -> Result<Car> {
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(10);
+        loop {
+            let cars = dql_select_cars(ditto, color).await?;
+            if let Some(car) = cars.into_iter().find(|car| car.make == make && predicate(car)) {
+                return Ok(car);
+            }
+            if tokio::time::Instant::now() >= deadline {
+                anyhow::bail!("timed out waiting for car with make={make} to converge");
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+        }
+    }
+
+    /// Two offline peers that each apply a PN_INCREMENT to the same car's
+    /// view_count should converge on the sum of their increments rather than
+    /// one peer's write clobbering the other's
+    #[tokio::test]
+    async fn increment_views_merges_across_peers() -> Result<()> {
+        let app_id = AppId::from_str("00000000-0000-0000-0000-000000000000")?;
+        let peer_a = offline_ditto(app_id, "a")?;
+        let peer_b = offline_ditto(app_id, "b")?;
+
+        let car = Car::new("blue".to_string(), "ditto-motors".to_string());
+        dql_insert_car(&peer_a, &car).await?;
+
+        // Wait for the new car to sync to peer_b before peer_b increments it
+        wait_for_car(&peer_b, "blue", "ditto-motors", |_| true).await?;
+
+        dql_increment_views(&peer_a, "ditto-motors").await?;
+        dql_increment_views(&peer_b, "ditto-motors").await?;
+
+        // Wait for both increments to propagate and merge back on peer_a
+        let merged = wait_for_car(&peer_a, "blue", "ditto-motors", |car| car.view_count == 2).await?;
+        assert_eq!(merged.view_count, 2);
+
+        Ok(())
+    }
+}
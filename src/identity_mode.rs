@@ -0,0 +1,103 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use dittolive_ditto::{identity::*, prelude::*};
+use std::{fs, path::PathBuf, sync::Arc};
+
+/// Identity modes supported by these samples, mirroring the identity types
+/// under `dittolive_ditto::identity`
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum IdentityMode {
+    /// Cloud-backed playground identity using an AppID and PlaygroundToken (default)
+    OnlinePlayground,
+    /// Fully peer-to-peer, with no cloud sync at all
+    OfflinePlayground,
+    /// A pre-shared key known to every peer, with no cloud auth service
+    SharedKey,
+    /// A pre-minted JWT/identity token, as a real backend would hand out
+    Manual,
+}
+
+/// The flags every sample's `Args` flattens in to select and configure an
+/// [`IdentityMode`]. Only the field(s) the chosen mode actually needs have
+/// to be supplied; the rest stay `None`.
+#[derive(Debug, clap::Args)]
+pub struct IdentityArgs {
+    /// How to identify and authenticate this peer with Ditto
+    #[clap(long, value_enum, default_value = "online-playground")]
+    pub identity: IdentityMode,
+
+    /// The Playground token used to authenticate, required when
+    /// `--identity=online-playground` (found at <https://portal.ditto.live>)
+    #[clap(long, env = "PLAYGROUND_TOKEN")]
+    pub playground_token: Option<String>,
+
+    /// A pre-shared key known to every peer, required when `--identity=shared-key`
+    #[clap(long, env = "SHARED_KEY")]
+    pub shared_key: Option<String>,
+
+    /// Path to a JWT/identity token file, required when `--identity=manual`
+    #[clap(long)]
+    pub identity_token_path: Option<PathBuf>,
+}
+
+/// An [`IdentityMode`] together with exactly the data it needs, checked
+/// up front so building the Ditto identity can't fail on a missing flag
+pub enum ResolvedIdentity {
+    OnlinePlayground { playground_token: String },
+    OfflinePlayground,
+    SharedKey { shared_key: String },
+    Manual { token: String },
+}
+
+impl IdentityArgs {
+    /// Validate that the value this mode needs was actually supplied,
+    /// reading the identity token file eagerly so a missing flag or an
+    /// unreadable file is reported here instead of from inside the Ditto
+    /// builder's identity closure
+    pub fn resolve(&self) -> Result<ResolvedIdentity> {
+        Ok(match self.identity {
+            IdentityMode::OnlinePlayground => ResolvedIdentity::OnlinePlayground {
+                playground_token: self.playground_token.clone().context(
+                    "--playground-token (or PLAYGROUND_TOKEN) is required when --identity=online-playground",
+                )?,
+            },
+            IdentityMode::OfflinePlayground => ResolvedIdentity::OfflinePlayground,
+            IdentityMode::SharedKey => ResolvedIdentity::SharedKey {
+                shared_key: self
+                    .shared_key
+                    .clone()
+                    .context("--shared-key is required when --identity=shared-key")?,
+            },
+            IdentityMode::Manual => {
+                let token_path = self
+                    .identity_token_path
+                    .clone()
+                    .context("--identity-token-path is required when --identity=manual")?;
+                let token = fs::read_to_string(&token_path).with_context(|| {
+                    format!("failed to read identity token file {}", token_path.display())
+                })?;
+                ResolvedIdentity::Manual { token }
+            }
+        })
+    }
+}
+
+/// Build the concrete Ditto `Identity` for a [`ResolvedIdentity`]
+pub fn build_identity(
+    resolved: ResolvedIdentity,
+    ditto_root: Arc<DittoRoot>,
+    app_id: AppId,
+) -> Result<Identity> {
+    let identity = match resolved {
+        ResolvedIdentity::OnlinePlayground { playground_token } => {
+            OnlinePlayground::new(ditto_root, app_id, playground_token, true, None)?
+        }
+        ResolvedIdentity::OfflinePlayground => OfflinePlayground::new(ditto_root, app_id)?,
+        ResolvedIdentity::SharedKey { shared_key } => {
+            SharedKey::new(ditto_root, app_id, shared_key, true)?
+        }
+        ResolvedIdentity::Manual { token } => Manual::new(ditto_root, app_id, token, true, None)?,
+    };
+
+    Ok(identity)
+}